@@ -1,8 +1,92 @@
-use rand::{rngs::SmallRng, SeedableRng, RngCore};
+use std::collections::VecDeque;
+
+use rand::{rngs::SmallRng, thread_rng, RngCore, SeedableRng};
+
+mod scheduler;
+use scheduler::Scheduler;
 
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 
+/// Number of (PC, opcode) pairs kept in the trace ring buffer.
+const TRACE_LEN: usize = 32;
+
+// A save with a `rng_draws` above this is corrupt, not just a long play
+// session — replaying it to restore RNG position is an O(n) loop, and
+// without a ceiling a corrupt-but-length-valid file (rng_draws up to
+// u64::MAX) turns `load_state` into an effective infinite loop.
+const MAX_RNG_DRAWS: u64 = 1_000_000_000;
+
+/// Behavioral toggles for the handful of instructions where different
+/// CHIP-8 interpreters disagree. ROMs are generally written against one
+/// of these behaviors, so the "correct" choice depends on what the ROM
+/// targeted rather than on the spec itself.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE: shift VX in place (true) instead of first copying VY into VX.
+    pub shift: bool,
+    /// FX55/FX65: leave `index_register` unchanged (true) instead of
+    /// incrementing it by X+1 after the load/store loop.
+    pub load_store: bool,
+    /// BNNN: jump to XNN + VX (true) instead of NNN + V0.
+    pub jump: bool,
+    /// 8XY1/8XY2/8XY3: reset VF to 0 after the operation.
+    pub vf_reset: bool,
+    /// DXYN: clip sprites at the display edge (true) instead of wrapping around.
+    pub clipping: bool,
+}
+
+impl Quirks {
+    /// COSMAC VIP behavior: the original, most widely emulated baseline.
+    pub fn cosmac_vip() -> Quirks {
+        Quirks {
+            shift: false,
+            load_store: false,
+            jump: false,
+            vf_reset: true,
+            clipping: true,
+        }
+    }
+
+    /// CHIP-48 behavior, as shipped on the HP-48 calculators.
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: true,
+            vf_reset: false,
+            clipping: true,
+        }
+    }
+
+    /// SUPER-CHIP behavior, used by most modern SCHIP/XO-CHIP ROMs.
+    pub fn superchip() -> Quirks {
+        Quirks {
+            shift: true,
+            load_store: true,
+            jump: true,
+            vf_reset: false,
+            clipping: false,
+        }
+    }
+
+    /// Looks up a preset by name, as accepted by the `--quirks` CLI flag.
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "cosmac-vip" => Some(Quirks::cosmac_vip()),
+            "chip48" => Some(Quirks::chip48()),
+            "superchip" => Some(Quirks::superchip()),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::cosmac_vip()
+    }
+}
+
 const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0,
     0x20, 0x60, 0x20, 0x20, 0x70,
@@ -32,11 +116,30 @@ pub struct Chip8 {
     delay_timer: u8,
     sound_timer: u8,
     rng: SmallRng,
+    // Seed behind `rng`, saved/restored with snapshots so a loaded state
+    // replays the same random sequence instead of the entropy-seeded
+    // default, which made runs non-repeatable across save/restore.
+    rng_seed: u64,
+    // Number of u32s drawn from `rng` since it was seeded. `SmallRng`
+    // exposes no way to read its internal position directly, so to
+    // restore the exact point in the stream a snapshot was taken at,
+    // `load_state` reseeds and then re-draws this many values.
+    rng_draws: u64,
     input: [bool; 16],
+    quirks: Quirks,
+    pc_history: VecDeque<(u16, u16)>,
+    scheduler: Scheduler,
 }
 
 impl Chip8 {
-    pub fn new() -> Chip8 {
+    /// `cycles_per_frame` is how many cycles the caller intends to run
+    /// per 60Hz frame (i.e. the `cycles` it will later pass to
+    /// [`Chip8::update`]); the scheduler's timer-tick and display-refresh
+    /// events are pinned against that same cadence (see
+    /// [`scheduler::Scheduler`]).
+    pub fn new(quirks: Quirks, cycles_per_frame: u32) -> Chip8 {
+        let rng_seed = thread_rng().next_u64();
+
         let mut chip8 = Chip8 {
             display: [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT],
             memory: [0; 4096],
@@ -46,8 +149,13 @@ impl Chip8 {
             index_register: 0,
             delay_timer: 0,
             sound_timer: 0,
-            rng: SmallRng::from_entropy(),
+            rng: SmallRng::seed_from_u64(rng_seed),
+            rng_seed,
+            rng_draws: 0,
             input: [false; 16],
+            quirks,
+            pc_history: VecDeque::with_capacity(TRACE_LEN),
+            scheduler: Scheduler::new(cycles_per_frame),
         };
 
         chip8.load_to_memory(&FONT, 0x050);
@@ -57,22 +165,32 @@ impl Chip8 {
 
         chip8
     }
-    
-    // Is called for every CPU cycle, which varies depending
-    // on settings
-    pub fn update(&mut self) {
-        let instruction = self.fetch_instruction();
-        self.execute_instruction(instruction);
-    }
-    
-    // Is called at a rate of approximately 60Hz
-    pub fn draw(&mut self) {
-        // Timers are decremented in draw phase because they
-        // should decrement 60 times per second
-        if self.delay_timer > 0 { self.delay_timer -= 1; }
-        if self.sound_timer > 0 { self.sound_timer -= 1; }
+
+    /// Runs `cycles` CPU cycles, draining any scheduler events that
+    /// became due along the way (timer decrements happen here rather
+    /// than in a `draw` phase). Returns whether a display refresh is due,
+    /// so the caller only has to redraw when the 60Hz event actually fires.
+    pub fn update(&mut self, cycles: u32) -> bool {
+        let mut should_redraw = false;
+
+        for _ in 0..cycles {
+            let instruction = self.fetch_instruction();
+            self.execute_instruction(instruction);
+
+            for event in self.scheduler.tick() {
+                match event {
+                    scheduler::Event::TimerTick => {
+                        if self.delay_timer > 0 { self.delay_timer -= 1; }
+                        if self.sound_timer > 0 { self.sound_timer -= 1; }
+                    },
+                    scheduler::Event::DisplayRefresh => should_redraw = true,
+                }
+            }
+        }
+
+        should_redraw
     }
-    
+
     pub fn load_to_memory(&mut self, data: &[u8], start_pos: usize) {
         for i in 0..data.len() {
             self.memory[start_pos+i] = data[i];
@@ -100,16 +218,167 @@ impl Chip8 {
         self.input[key] = value;
     }
 
+    // State accessors used by the gdbstub debug server to inspect and
+    // mutate the machine between single-step cycles.
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, index: usize, value: u8) {
+        self.registers[index] = value;
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn write_memory_byte(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+    }
+
     pub fn fetch_instruction(&mut self) -> u16 {
         let b1 = self.memory[self.pc as usize] as u16;
         let b2 = self.memory[(self.pc+1) as usize] as u16;
         let instruction = (b1 << 8) + b2;
 
+        if self.pc_history.len() == TRACE_LEN {
+            self.pc_history.pop_front();
+        }
+        self.pc_history.push_back((self.pc, instruction));
+
         self.pc += 2;
 
         instruction
     }
 
+    /// Prints the last [`TRACE_LEN`] fetched (PC, opcode) pairs, oldest
+    /// first, disassembled into mnemonics. Intended for use from a panic
+    /// hook so an out-of-bounds access leaves behind a readable
+    /// instruction history instead of a bare index panic. Unknown opcodes
+    /// do not panic (`execute_instruction` ignores them), so they do not
+    /// produce a dump.
+    pub fn dump_trace(&self) {
+        eprintln!("--- last {} instructions ---", self.pc_history.len());
+        for (pc, opcode) in &self.pc_history {
+            eprintln!("{:04x}: {:04x}  {}", pc, opcode, disassemble(*opcode));
+        }
+    }
+
+    /// Serializes the full machine state (display, memory, stack, pc,
+    /// registers, index register, timers, input and RNG seed/position) to
+    /// a compact byte blob. Should only be called at an instruction
+    /// boundary, i.e. after `update` completes and before the next
+    /// `fetch_instruction`, so the snapshot can't land mid-instruction.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::new();
+
+        state.extend_from_slice(&self.rng_seed.to_le_bytes());
+        state.extend_from_slice(&self.rng_draws.to_le_bytes());
+        state.extend_from_slice(&self.pc.to_le_bytes());
+        state.extend_from_slice(&self.index_register.to_le_bytes());
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.extend_from_slice(&self.registers);
+
+        for key in self.input {
+            state.push(key as u8);
+        }
+
+        state.extend_from_slice(&(self.stack.len() as u16).to_le_bytes());
+        for frame in &self.stack {
+            state.extend_from_slice(&frame.to_le_bytes());
+        }
+
+        state.extend_from_slice(&self.memory);
+
+        for row in &self.display {
+            for pixel in row {
+                state.push(*pixel as u8);
+            }
+        }
+
+        state
+    }
+
+    /// Restores state previously produced by [`Chip8::save_state`],
+    /// including reseeding the RNG and re-drawing it back to the exact
+    /// position it was at when the snapshot was taken, so playback after
+    /// the restore continues the same random sequence rather than
+    /// restarting it. Returns an error instead of panicking if `data` is
+    /// truncated or its RNG draw count exceeds `MAX_RNG_DRAWS`; other
+    /// field values (pc, stack contents) are not validated, and bytes past
+    /// the end of the expected layout are ignored.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let mut cursor = StateCursor::new(data);
+
+        let rng_seed = cursor.read_u64()?;
+        let rng_draws = cursor.read_u64()?;
+        if rng_draws > MAX_RNG_DRAWS {
+            return Err("Error: save state is corrupt (implausible RNG draw count)");
+        }
+        let pc = cursor.read_u16()?;
+        let index_register = cursor.read_u16()?;
+        let delay_timer = cursor.read_u8()?;
+        let sound_timer = cursor.read_u8()?;
+        let registers = cursor.read_array::<16>()?;
+
+        let mut input = [false; 16];
+        for key in input.iter_mut() {
+            *key = cursor.read_u8()? != 0;
+        }
+
+        let stack_len = cursor.read_u16()? as usize;
+        let mut stack = Vec::with_capacity(stack_len);
+        for _ in 0..stack_len {
+            stack.push(cursor.read_u16()?);
+        }
+
+        let memory = cursor.read_array::<4096>()?;
+
+        let mut display = [[false; DISPLAY_WIDTH]; DISPLAY_HEIGHT];
+        for row in display.iter_mut() {
+            for pixel in row.iter_mut() {
+                *pixel = cursor.read_u8()? != 0;
+            }
+        }
+
+        self.rng_seed = rng_seed;
+        self.rng_draws = rng_draws;
+        self.rng = SmallRng::seed_from_u64(rng_seed);
+        for _ in 0..rng_draws {
+            self.rng.next_u32();
+        }
+
+        self.pc = pc;
+        self.index_register = index_register;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.registers = registers;
+        self.input = input;
+        self.stack = stack;
+        self.memory = memory;
+        self.display = display;
+
+        Ok(())
+    }
+
     pub fn execute_instruction(&mut self, instruction: u16) {
         // Split instruction into four half-bytes
         let n1 = ((instruction >> 12) & 0xf) as u8;
@@ -191,14 +460,17 @@ impl Chip8 {
                     // 8XY1: Binary OR
                     0x1 => {
                         self.registers[n2 as usize] |= self.registers[n3 as usize];
+                        if self.quirks.vf_reset { self.registers[0xf] = 0; }
                     },
                     // 8XY2: Binary AND
                     0x2 => {
                         self.registers[n2 as usize] &= self.registers[n3 as usize];
+                        if self.quirks.vf_reset { self.registers[0xf] = 0; }
                     },
                     // 8XY3: Logical XOR
                     0x3 => {
                         self.registers[n2 as usize] ^= self.registers[n3 as usize];
+                        if self.quirks.vf_reset { self.registers[0xf] = 0; }
                     },
                     // 8XY4: Add
                     0x4 => {
@@ -219,7 +491,9 @@ impl Chip8 {
                     },
                     // 8XY6: Shift right
                     0x6 => {
-                        //self.registers[n2 as usize] = self.registers[n3 as usize];
+                        if !self.quirks.shift {
+                            self.registers[n2 as usize] = self.registers[n3 as usize];
+                        }
                         self.registers[0xf] = self.registers[n2 as usize] & 1;
                         self.registers[n2 as usize] >>= 1;
                     }
@@ -235,7 +509,9 @@ impl Chip8 {
                     },
                     // 8XYE: Shift left
                     0xe => {
-                        //self.registers[n2 as usize] = self.registers[n3 as usize];
+                        if !self.quirks.shift {
+                            self.registers[n2 as usize] = self.registers[n3 as usize];
+                        }
                         self.registers[0xf] = (self.registers[n2 as usize] >> 7) & 1;
                         self.registers[n2 as usize] <<= 1;
                     }
@@ -254,12 +530,19 @@ impl Chip8 {
             },
             // BNNN: Jump with offset
             0xb => {
-                self.pc = n2n3n4 + self.registers[0] as u16;
+                if self.quirks.jump {
+                    // BXNN: jump to XNN plus VX
+                    self.pc = n2n3n4 + self.registers[n2 as usize] as u16;
+                } else {
+                    // BNNN: jump to NNN plus V0
+                    self.pc = n2n3n4 + self.registers[0] as u16;
+                }
             },
             // CXNN: Random
             0xc => {
                 // Generate 4-bit random integer
                 let random = (self.rng.next_u32() & 0b1111) as u8;
+                self.rng_draws += 1;
 
                 self.registers[n2 as usize] = random & n3n4;
             },
@@ -335,12 +618,14 @@ impl Chip8 {
                         for i in 0..n2+1 {
                             self.memory[(self.index_register + i as u16) as usize] = self.registers[i as usize];
                         }
+                        if !self.quirks.load_store { self.index_register += n2 as u16 + 1; }
                     },
                     // FX65: Load registers from memory
                     0x65 => {
                         for i in 0..n2+1 {
                             self.registers[i as usize] = self.memory[(self.index_register + i as u16) as usize];
                         }
+                        if !self.quirks.load_store { self.index_register += n2 as u16 + 1; }
                     },
                     _ => {}
                 }
@@ -362,20 +647,26 @@ impl Chip8 {
 
         let mut index = self.index_register as usize;
         for i in 0..height {
-            if y+i > DISPLAY_HEIGHT as u8 { break; }
+            let row = (y as usize) + (i as usize);
+            // Clipping mode stops drawing the rest of the sprite once it
+            // runs off the bottom edge; wrapping mode instead draws every
+            // row, wrapped back onto the display.
+            if self.quirks.clipping && row >= DISPLAY_HEIGHT { break; }
+            let row = row % DISPLAY_HEIGHT;
 
             let byte = self.memory[index];
             for j in 0..8 {
-                // Stop drawing row if outside display bounds
-                if x+j > DISPLAY_WIDTH as u8 { break; }
+                let col = (x as usize) + (j as usize);
+                if self.quirks.clipping && col >= DISPLAY_WIDTH { break; }
+                let col = col % DISPLAY_WIDTH;
 
                 // Get pixel value from sprite data in memory
                 let sprite_value = ((byte >> (7 - j)) & 1) == 1;
 
                 if sprite_value {
                     // Flip corresponding bit on screen
-                    if let Some(screen_value) = self.get_pixel((x+j) as usize, (y+i) as usize) {
-                        self.set_pixel((x+j) as usize, (y+i) as usize, !screen_value);
+                    if let Some(screen_value) = self.get_pixel(col, row) {
+                        self.set_pixel(col, row, !screen_value);
 
                         if screen_value { self.registers[15] = 1; }
                     }
@@ -395,3 +686,99 @@ impl Chip8 {
         println!("\n");
     }
 }
+
+/// Bounds-checked reader over a `load_state` byte blob, so a truncated or
+/// corrupt `.state` file produces an error instead of an indexing panic.
+struct StateCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateCursor<'a> {
+    fn new(data: &'a [u8]) -> StateCursor<'a> {
+        StateCursor { data, pos: 0 }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], &'static str> {
+        let end = self.pos + len;
+        let slice = self.data.get(self.pos..end).ok_or("Error: save state is truncated")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, &'static str> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16, &'static str> {
+        Ok(u16::from_le_bytes(self.read_bytes(2)?.try_into().unwrap()))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, &'static str> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], &'static str> {
+        Ok(self.read_bytes(N)?.try_into().unwrap())
+    }
+}
+
+/// Decodes a raw opcode into a human-readable mnemonic. Mirrors the
+/// n1..n4 nibble split in [`Chip8::execute_instruction`] but only reads
+/// the opcode, so it can be used for tracing without a `Chip8` instance.
+pub fn disassemble(instruction: u16) -> String {
+    let n1 = ((instruction >> 12) & 0xf) as u8;
+    let n2 = ((instruction >> 8) & 0xf) as u8;
+    let n3 = ((instruction >> 4) & 0xf) as u8;
+    let n4 = (instruction & 0xf) as u8;
+    let n3n4 = (instruction & 0xff) as u8;
+    let n2n3n4 = instruction & 0xfff;
+
+    match n1 {
+        0x0 if instruction == 0x00e0 => "CLS".to_string(),
+        0x0 if instruction == 0x00ee => "RET".to_string(),
+        0x0 => format!("SYS  0x{n2n3n4:03x}"),
+        0x1 => format!("JP   0x{n2n3n4:03x}"),
+        0x2 => format!("CALL 0x{n2n3n4:03x}"),
+        0x3 => format!("SE   V{n2:x}, 0x{n3n4:02x}"),
+        0x4 => format!("SNE  V{n2:x}, 0x{n3n4:02x}"),
+        0x5 => format!("SE   V{n2:x}, V{n3:x}"),
+        0x6 => format!("LD   V{n2:x}, 0x{n3n4:02x}"),
+        0x7 => format!("ADD  V{n2:x}, 0x{n3n4:02x}"),
+        0x8 => match n4 {
+            0x0 => format!("LD   V{n2:x}, V{n3:x}"),
+            0x1 => format!("OR   V{n2:x}, V{n3:x}"),
+            0x2 => format!("AND  V{n2:x}, V{n3:x}"),
+            0x3 => format!("XOR  V{n2:x}, V{n3:x}"),
+            0x4 => format!("ADD  V{n2:x}, V{n3:x}"),
+            0x5 => format!("SUB  V{n2:x}, V{n3:x}"),
+            0x6 => format!("SHR  V{n2:x}, V{n3:x}"),
+            0x7 => format!("SUBN V{n2:x}, V{n3:x}"),
+            0xe => format!("SHL  V{n2:x}, V{n3:x}"),
+            _ => format!("??? 0x{instruction:04x}"),
+        },
+        0x9 => format!("SNE  V{n2:x}, V{n3:x}"),
+        0xa => format!("LD   I, 0x{n2n3n4:03x}"),
+        0xb => format!("JP   V0, 0x{n2n3n4:03x}"),
+        0xc => format!("RND  V{n2:x}, 0x{n3n4:02x}"),
+        0xd => format!("DRW  V{n2:x}, V{n3:x}, {n4}"),
+        0xe => match n3n4 {
+            0x9e => format!("SKP  V{n2:x}"),
+            0xa1 => format!("SKNP V{n2:x}"),
+            _ => format!("??? 0x{instruction:04x}"),
+        },
+        0xf => match n3n4 {
+            0x07 => format!("LD   V{n2:x}, DT"),
+            0x0a => format!("LD   V{n2:x}, K"),
+            0x15 => format!("LD   DT, V{n2:x}"),
+            0x18 => format!("LD   ST, V{n2:x}"),
+            0x1e => format!("ADD  I, V{n2:x}"),
+            0x29 => format!("LD   F, V{n2:x}"),
+            0x33 => format!("LD   B, V{n2:x}"),
+            0x55 => format!("LD   [I], V{n2:x}"),
+            0x65 => format!("LD   V{n2:x}, [I]"),
+            _ => format!("??? 0x{instruction:04x}"),
+        },
+        _ => format!("??? 0x{instruction:04x}"),
+    }
+}
@@ -0,0 +1,81 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Housekeeping the scheduler fires once enough CPU cycles have elapsed.
+/// Both are pinned to 60Hz regardless of CPU clock speed, so they're
+/// driven off the cycle counter rather than off `draw`/wall-clock sleeps.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// Decrement the delay and sound timers.
+    TimerTick,
+    /// Ask the caller to present the display.
+    DisplayRefresh,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Scheduled {
+    due: u64,
+    event: Event,
+}
+
+impl Ord for Scheduled {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // BinaryHeap is a max-heap; reverse `due` so the earliest due
+        // cycle pops first, then break ties on `event` so this agrees
+        // with the derived `Eq` (equal only when both fields match).
+        other.due.cmp(&self.due).then_with(|| self.event.cmp(&other.event))
+    }
+}
+
+impl PartialOrd for Scheduled {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A small priority queue of future events keyed by CPU cycle count,
+/// replacing a fixed per-cycle sleep loop. Decoupling 60Hz housekeeping
+/// from wall-clock sleeps means CPU speed no longer drifts against the
+/// host's sleep granularity, and running more cycles per call (see
+/// `Chip8::update`) fast-forwards both the CPU and the 60Hz events together.
+pub struct Scheduler {
+    cycle: u64,
+    cycles_per_tick: u64,
+    queue: BinaryHeap<Scheduled>,
+}
+
+impl Scheduler {
+    /// `cycles_per_frame` is how many CPU cycles the caller actually runs
+    /// per 60Hz video frame (see `Chip8::update`). 60Hz events must be
+    /// scheduled on that same cadence — not derived separately from a
+    /// nominal CPU clock — or they drift off 60Hz whenever the executed
+    /// cycle count and the clock speed differ (e.g. `--ipf` overriding
+    /// the default derived from `--hz`).
+    pub fn new(cycles_per_frame: u32) -> Scheduler {
+        let cycles_per_tick = (cycles_per_frame as u64).max(1);
+
+        let mut queue = BinaryHeap::new();
+        queue.push(Scheduled { due: cycles_per_tick, event: Event::TimerTick });
+        queue.push(Scheduled { due: cycles_per_tick, event: Event::DisplayRefresh });
+
+        Scheduler { cycle: 0, cycles_per_tick, queue }
+    }
+
+    /// Advances the cycle counter by one and drains any events that are
+    /// now due, in timestamp order, rescheduling each for its next tick.
+    pub fn tick(&mut self) -> Vec<Event> {
+        self.cycle += 1;
+
+        let mut due = Vec::new();
+        while matches!(self.queue.peek(), Some(scheduled) if scheduled.due <= self.cycle) {
+            let scheduled = self.queue.pop().unwrap();
+            due.push(scheduled.event);
+            self.queue.push(Scheduled {
+                due: self.cycle + self.cycles_per_tick,
+                event: scheduled.event,
+            });
+        }
+
+        due
+    }
+}
@@ -0,0 +1,247 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEventKind, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::terminal::{disable_raw_mode, enable_raw_mode, supports_keyboard_enhancement};
+use crossterm::execute;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+
+use crate::chip8::{self, Chip8};
+use crate::{INPUT_MAP, TERMINAL_INPUT_MAP};
+
+/// Abstracts the render/input loop so `main` can run the same emulation
+/// loop against either the minifb window or the headless terminal
+/// renderer without branching on every frame.
+pub trait Frontend {
+    /// Returns false once the user has asked to quit.
+    fn is_running(&mut self) -> bool;
+    /// Samples the current input state into the chip8's key matrix.
+    fn poll_input(&mut self, chip8: &mut Chip8);
+    /// Renders one frame of the chip8's display buffer.
+    fn draw(&mut self, chip8: &Chip8);
+
+    /// Consumes a pending save-state hotkey press (F5), if any. Frontends
+    /// without a save-state hotkey can rely on the default.
+    fn take_save_request(&mut self) -> bool {
+        false
+    }
+
+    /// Consumes a pending load-state hotkey press (F9), if any.
+    fn take_load_request(&mut self) -> bool {
+        false
+    }
+}
+
+pub struct MinifbFrontend {
+    window: Window,
+}
+
+impl MinifbFrontend {
+    pub fn new() -> MinifbFrontend {
+        let mut window = Window::new(
+            "chip8-rs",
+            chip8::DISPLAY_WIDTH,
+            chip8::DISPLAY_HEIGHT,
+            WindowOptions {
+                resize: false,
+                scale: minifb::Scale::X8,
+                ..WindowOptions::default()
+            }
+        )
+        .unwrap_or_else(|e| {
+            panic!("{}", e);
+        });
+
+        window.limit_update_rate(None);
+
+        MinifbFrontend { window }
+    }
+}
+
+impl Frontend for MinifbFrontend {
+    fn is_running(&mut self) -> bool {
+        self.window.is_open() && !self.window.is_key_down(Key::Escape)
+    }
+
+    fn poll_input(&mut self, chip8: &mut Chip8) {
+        for i in 0..INPUT_MAP.len() {
+            chip8.set_key(i, self.window.is_key_down(INPUT_MAP[i]));
+        }
+    }
+
+    fn draw(&mut self, chip8: &Chip8) {
+        let buffer = translate_display(chip8.get_display());
+        self.window
+            .update_with_buffer(&buffer, chip8::DISPLAY_WIDTH, chip8::DISPLAY_HEIGHT)
+            .unwrap();
+    }
+
+    fn take_save_request(&mut self) -> bool {
+        self.window.is_key_pressed(Key::F5, KeyRepeat::No)
+    }
+
+    fn take_load_request(&mut self) -> bool {
+        self.window.is_key_pressed(Key::F9, KeyRepeat::No)
+    }
+}
+
+// Translates the chip8's monochrome display buffer to a buffer that can be sent to minifb
+fn translate_display(chip8_buffer: &[[bool; chip8::DISPLAY_WIDTH]; chip8::DISPLAY_HEIGHT])
+    -> [u32; chip8::DISPLAY_WIDTH * chip8::DISPLAY_HEIGHT] {
+    let mut window_buffer = [0; chip8::DISPLAY_WIDTH * chip8::DISPLAY_HEIGHT];
+
+    for i in 0..chip8_buffer.len() {
+        for j in 0..chip8_buffer[i].len() {
+            window_buffer[j + (i * chip8::DISPLAY_WIDTH)] = if chip8_buffer[i][j] == true {
+                0xFFFFFFFF
+            } else {
+                0x00000000
+            };
+        }
+    }
+
+    window_buffer
+}
+
+// Without the Kitty keyboard protocol, most terminals only ever report a
+// key Press (a held key just repeats Press at the terminal's autorepeat
+// rate) and never a Release, so a key would otherwise latch on forever.
+// When the protocol isn't available, treat a key as released once this
+// long has passed without a fresh Press.
+const KEY_RELEASE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Renders the display to an ANSI terminal over stdout, packing two
+/// vertical pixels per character cell with half-block glyphs, and reads
+/// keys from stdin in raw mode. Lets the emulator run over SSH or in CI
+/// where no window can be opened.
+pub struct TerminalFrontend {
+    running: bool,
+    input_state: [bool; 16],
+    last_pressed: [Option<Instant>; 16],
+    save_requested: bool,
+    load_requested: bool,
+    // Whether the terminal supports the Kitty keyboard protocol, in which
+    // case it sends real Release events and the autorepeat timeout below
+    // is unnecessary.
+    enhanced_keyboard: bool,
+}
+
+impl TerminalFrontend {
+    pub fn new() -> TerminalFrontend {
+        enable_raw_mode().expect("failed to enable raw terminal mode");
+        print!("\x1b[?25l"); // hide cursor
+        let _ = std::io::stdout().flush();
+
+        let enhanced_keyboard = supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced_keyboard {
+            let _ = execute!(
+                std::io::stdout(),
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            );
+        }
+
+        TerminalFrontend {
+            running: true,
+            input_state: [false; 16],
+            last_pressed: [None; 16],
+            save_requested: false,
+            load_requested: false,
+            enhanced_keyboard,
+        }
+    }
+}
+
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        if self.enhanced_keyboard {
+            let _ = execute!(std::io::stdout(), PopKeyboardEnhancementFlags);
+        }
+        print!("\x1b[?25h"); // restore cursor
+        let _ = std::io::stdout().flush();
+        let _ = disable_raw_mode();
+    }
+}
+
+impl Frontend for TerminalFrontend {
+    fn is_running(&mut self) -> bool {
+        self.running
+    }
+
+    fn poll_input(&mut self, chip8: &mut Chip8) {
+        while event::poll(Duration::from_secs(0)).unwrap_or(false) {
+            if let Ok(Event::Key(key_event)) = event::read() {
+                let pressed = key_event.kind != KeyEventKind::Release;
+
+                if key_event.code == KeyCode::Esc && pressed {
+                    self.running = false;
+                }
+                if key_event.code == KeyCode::F(5) && pressed {
+                    self.save_requested = true;
+                }
+                if key_event.code == KeyCode::F(9) && pressed {
+                    self.load_requested = true;
+                }
+
+                for i in 0..TERMINAL_INPUT_MAP.len() {
+                    if TERMINAL_INPUT_MAP[i] == key_event.code {
+                        self.input_state[i] = pressed;
+                        self.last_pressed[i] = if pressed { Some(Instant::now()) } else { None };
+                    }
+                }
+            }
+        }
+
+        if !self.enhanced_keyboard {
+            let now = Instant::now();
+            for i in 0..self.input_state.len() {
+                if self.input_state[i] {
+                    let stale = self.last_pressed[i]
+                        .map(|seen| now.duration_since(seen) > KEY_RELEASE_TIMEOUT)
+                        .unwrap_or(true);
+                    if stale {
+                        self.input_state[i] = false;
+                    }
+                }
+            }
+        }
+
+        for i in 0..self.input_state.len() {
+            chip8.set_key(i, self.input_state[i]);
+        }
+    }
+
+    fn draw(&mut self, chip8: &Chip8) {
+        let display = chip8.get_display();
+
+        // Cursor-home rather than clear-screen, so the grid redraws in
+        // place instead of flickering.
+        let mut frame = String::from("\x1b[H");
+        for row in 0..(chip8::DISPLAY_HEIGHT / 2) {
+            for col in 0..chip8::DISPLAY_WIDTH {
+                let top = display[row * 2][col];
+                let bottom = display[row * 2 + 1][col];
+                frame.push(match (top, bottom) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                });
+            }
+            frame.push_str("\r\n");
+        }
+
+        print!("{frame}");
+        let _ = std::io::stdout().flush();
+    }
+
+    fn take_save_request(&mut self) -> bool {
+        std::mem::take(&mut self.save_requested)
+    }
+
+    fn take_load_request(&mut self) -> bool {
+        std::mem::take(&mut self.load_requested)
+    }
+}
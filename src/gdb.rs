@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use gdbstub::common::Signal;
+use gdbstub::conn::ConnectionExt;
+use gdbstub::stub::{DisconnectReason, GdbStub, SingleThreadStopReason};
+use gdbstub::target::ext::base::singlethread::{
+    SingleThreadBase, SingleThreadResume, SingleThreadResumeOps, SingleThreadSingleStep,
+    SingleThreadSingleStepOps,
+};
+use gdbstub::target::ext::base::BaseOps;
+use gdbstub::target::ext::breakpoints::{Breakpoints, BreakpointsOps, SwBreakpoint, SwBreakpointOps};
+use gdbstub::target::{Target, TargetError, TargetResult};
+use gdbstub_arch::Arch;
+
+use crate::chip8::Chip8;
+
+/// Register file as gdbstub wants it: the 16 V-registers followed by
+/// PC and I, matching how a CHIP-8 client would lay out its `.gdbinit`.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct Chip8Registers {
+    pub v: [u8; 16],
+    pub pc: u16,
+    pub i: u16,
+}
+
+impl gdbstub::arch::Registers for Chip8Registers {
+    type ProgramCounter = u16;
+
+    fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    fn gdb_serialize(&self, mut write_byte: impl FnMut(Option<u8>)) {
+        for v in self.v {
+            write_byte(Some(v));
+        }
+        for b in self.pc.to_le_bytes() {
+            write_byte(Some(b));
+        }
+        for b in self.i.to_le_bytes() {
+            write_byte(Some(b));
+        }
+    }
+
+    fn gdb_deserialize(&mut self, bytes: &[u8]) -> Result<(), ()> {
+        if bytes.len() < 20 {
+            return Err(());
+        }
+        self.v.copy_from_slice(&bytes[0..16]);
+        self.pc = u16::from_le_bytes([bytes[16], bytes[17]]);
+        self.i = u16::from_le_bytes([bytes[18], bytes[19]]);
+        Ok(())
+    }
+}
+
+/// Minimal `Arch` describing CHIP-8 to gdbstub: 16-bit addresses, no
+/// target description XML, registers as defined above.
+pub enum Chip8Arch {}
+
+impl Arch for Chip8Arch {
+    type Usize = u16;
+    type Registers = Chip8Registers;
+    type BreakpointKind = usize;
+    type RegId = ();
+
+    fn target_description_xml() -> Option<&'static str> {
+        None
+    }
+}
+
+/// Wraps a [`Chip8`] so it can be single-stepped and inspected by a GDB
+/// (or any gdbstub-compatible) client over TCP, the same approach the
+/// zba GBA emulator takes with its gdbstub submodule.
+pub struct GdbTarget {
+    pub chip8: Chip8,
+    breakpoints: HashSet<u16>,
+}
+
+impl GdbTarget {
+    pub fn new(chip8: Chip8) -> GdbTarget {
+        GdbTarget { chip8, breakpoints: HashSet::new() }
+    }
+
+    fn hit_breakpoint(&self) -> bool {
+        self.breakpoints.contains(&self.chip8.pc())
+    }
+}
+
+impl Target for GdbTarget {
+    type Arch = Chip8Arch;
+    type Error = &'static str;
+
+    fn base_ops(&mut self) -> BaseOps<'_, Self::Arch, Self::Error> {
+        BaseOps::SingleThread(self)
+    }
+
+    fn support_breakpoints(&mut self) -> Option<BreakpointsOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadBase for GdbTarget {
+    fn read_registers(&mut self, regs: &mut Chip8Registers) -> TargetResult<(), Self> {
+        regs.v.copy_from_slice(self.chip8.registers());
+        regs.pc = self.chip8.pc();
+        regs.i = self.chip8.index_register();
+        Ok(())
+    }
+
+    fn write_registers(&mut self, regs: &Chip8Registers) -> TargetResult<(), Self> {
+        for (i, v) in regs.v.iter().enumerate() {
+            self.chip8.set_register(i, *v);
+        }
+        self.chip8.set_pc(regs.pc);
+        self.chip8.set_index_register(regs.i);
+        Ok(())
+    }
+
+    fn read_addrs(&mut self, start_addr: u16, data: &mut [u8]) -> TargetResult<usize, Self> {
+        let memory = self.chip8.memory();
+        for (offset, byte) in data.iter_mut().enumerate() {
+            let addr = start_addr as usize + offset;
+            *byte = *memory.get(addr).ok_or(TargetError::NonFatal)?;
+        }
+        Ok(data.len())
+    }
+
+    fn write_addrs(&mut self, start_addr: u16, data: &[u8]) -> TargetResult<(), Self> {
+        for (offset, byte) in data.iter().enumerate() {
+            self.chip8.write_memory_byte(start_addr as usize + offset, *byte);
+        }
+        Ok(())
+    }
+
+    fn support_resume(&mut self) -> Option<SingleThreadResumeOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadResume for GdbTarget {
+    fn resume(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn support_single_step(&mut self) -> Option<SingleThreadSingleStepOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SingleThreadSingleStep for GdbTarget {
+    fn step(&mut self, _signal: Option<Signal>) -> Result<(), Self::Error> {
+        self.chip8.update(1);
+        Ok(())
+    }
+}
+
+impl Breakpoints for GdbTarget {
+    fn support_sw_breakpoint(&mut self) -> Option<SwBreakpointOps<'_, Self>> {
+        Some(self)
+    }
+}
+
+impl SwBreakpoint for GdbTarget {
+    fn add_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.breakpoints.insert(addr))
+    }
+
+    fn remove_sw_breakpoint(&mut self, addr: u16, _kind: usize) -> TargetResult<bool, Self> {
+        Ok(self.breakpoints.remove(&addr))
+    }
+}
+
+/// Runs `target` under a blocking gdbstub session on `port`, in place of
+/// the free-running frame loop, yielding control to GDB's halt/continue/step
+/// commands until the client disconnects.
+pub fn serve(target: GdbTarget, port: u16) -> std::io::Result<()> {
+    let listener = std::net::TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for a GDB connection on 127.0.0.1:{port}...");
+    let (stream, addr) = listener.accept()?;
+    println!("Debugger connected from {addr}");
+
+    let connection: Box<dyn ConnectionExt<Error = std::io::Error>> = Box::new(stream);
+    let mut target = target;
+    let stub = GdbStub::new(connection);
+
+    match stub.run_blocking::<GdbBlockingEventLoop>(&mut target) {
+        Ok(DisconnectReason::TargetExited(_)) | Ok(DisconnectReason::Disconnect) => {}
+        Ok(reason) => println!("GDB session ended: {reason:?}"),
+        Err(e) => println!("GDB session error: {e}"),
+    }
+
+    Ok(())
+}
+
+struct GdbBlockingEventLoop;
+
+impl gdbstub::stub::run_blocking::BlockingEventLoop for GdbBlockingEventLoop {
+    type Target = GdbTarget;
+    type Connection = Box<dyn ConnectionExt<Error = std::io::Error>>;
+    type StopReason = SingleThreadStopReason<u16>;
+
+    fn wait_for_stop_reason(
+        target: &mut GdbTarget,
+        conn: &mut Self::Connection,
+    ) -> Result<
+        gdbstub::stub::run_blocking::Event<Self::StopReason>,
+        gdbstub::stub::run_blocking::WaitForStopReasonError<
+            <Self::Target as Target>::Error,
+            <Self::Connection as gdbstub::conn::Connection>::Error,
+        >,
+    > {
+        // `continue` should free-run to the next breakpoint rather than
+        // stopping after a single instruction, so keep cycling here
+        // instead of returning a stop reason every call.
+        loop {
+            if conn.peek().map(|b| b.is_some()).unwrap_or(false) {
+                let byte = conn
+                    .read()
+                    .map_err(gdbstub::stub::run_blocking::WaitForStopReasonError::Connection)?;
+                return Ok(gdbstub::stub::run_blocking::Event::IncomingData(byte));
+            }
+
+            target.chip8.update(1);
+
+            if target.hit_breakpoint() {
+                return Ok(gdbstub::stub::run_blocking::Event::TargetStopped(
+                    SingleThreadStopReason::SwBreak(()),
+                ));
+            }
+        }
+    }
+
+    fn on_interrupt(
+        _target: &mut GdbTarget,
+    ) -> Result<Option<Self::StopReason>, <Self::Target as Target>::Error> {
+        Ok(Some(SingleThreadStopReason::Signal(Signal::SIGINT)))
+    }
+}
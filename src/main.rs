@@ -1,12 +1,20 @@
+use std::cell::Cell;
 use std::{env, fs, process, thread, time};
-use minifb::{Key, Window, WindowOptions};
+use crossterm::event::KeyCode;
+use minifb::Key;
 use rodio::{OutputStream, Sink};
 use rodio::source::{SineWave, Source};
 
 mod chip8;
+mod frontend;
+mod gdb;
 
-const CYCLES_PER_SECOND: f32 = 700.0;
-const MICROSECONDS_PER_CYCLE: u128 = ((1.0 / CYCLES_PER_SECOND) * 1_000_000.0) as u128;
+use frontend::Frontend;
+
+// CPU clock used unless overridden by --hz; also the basis for the
+// default --ipf (instructions per video frame) of DEFAULT_CPU_HZ / 60.
+const DEFAULT_CPU_HZ: u32 = 700;
+const FRAME_MICROS: u64 = 1_000_000 / 60;
 
 const INPUT_MAP: [Key; 16] = [
     Key::X, /* 0 */
@@ -27,6 +35,50 @@ const INPUT_MAP: [Key; 16] = [
     Key::V, /* F */
     ];
 
+// Same layout as INPUT_MAP, for the --terminal frontend which reads keys
+// via crossterm instead of minifb.
+const TERMINAL_INPUT_MAP: [KeyCode; 16] = [
+    KeyCode::Char('x'), /* 0 */
+    KeyCode::Char('1'), /* 1 */
+    KeyCode::Char('2'), /* 2 */
+    KeyCode::Char('3'), /* 3 */
+    KeyCode::Char('q'), /* 4 */
+    KeyCode::Char('w'), /* 5 */
+    KeyCode::Char('e'), /* 6 */
+    KeyCode::Char('a'), /* 7 */
+    KeyCode::Char('s'), /* 8 */
+    KeyCode::Char('d'), /* 9 */
+    KeyCode::Char('z'), /* A */
+    KeyCode::Char('c'), /* B */
+    KeyCode::Char('4'), /* C */
+    KeyCode::Char('r'), /* D */
+    KeyCode::Char('f'), /* E */
+    KeyCode::Char('v'), /* F */
+    ];
+
+thread_local! {
+    // Raw pointer to the running Chip8, set once before the main loop
+    // starts so the panic hook below can dump its instruction trace even
+    // though a panic handler can't otherwise reach local state on the
+    // stack. Cleared whenever `chip8` is moved out from under it (see the
+    // `--debug` branch) so the hook never dereferences a dangling pointer.
+    static CURRENT_CHIP8: Cell<*const chip8::Chip8> = Cell::new(std::ptr::null());
+}
+
+fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        CURRENT_CHIP8.with(|current| {
+            let ptr = current.get();
+            if !ptr.is_null() {
+                // Safety: only ever set to the address of the `chip8` local
+                // in `main`, which outlives the hook for the program's life.
+                unsafe { (*ptr).dump_trace(); }
+            }
+        });
+        eprintln!("{info}");
+    }));
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let config = parse_arguments(&args).unwrap_or_else(|err| {
@@ -34,9 +86,9 @@ fn main() {
         process::exit(1);
     });
 
-    let mut chip8 = chip8::Chip8::new();
-
-    let mut window = initialize_window();
+    let mut chip8 = chip8::Chip8::new(config.quirks, config.instructions_per_frame);
+    CURRENT_CHIP8.with(|current| current.set(&chip8));
+    install_panic_hook();
 
     let path = format!("roms/{}", config.rom_path);
     let program = fs::read(&path).unwrap_or_else(|_e| {
@@ -46,81 +98,78 @@ fn main() {
     chip8.load_to_memory(&program, 0x200);
     //chip8.print_memory();
 
+    // Under --debug, GDB drives every cycle via halt/continue/step instead
+    // of the free-running frame loop below.
+    if let Some(port) = config.debug_port {
+        // `chip8` moves into the GdbTarget below, so the pointer we set
+        // above would otherwise dangle for the rest of the program.
+        CURRENT_CHIP8.with(|current| current.set(std::ptr::null()));
+        gdb::serve(gdb::GdbTarget::new(chip8), port).unwrap_or_else(|e| {
+            println!("Error: debug server failed: {e}");
+            process::exit(1);
+        });
+        return;
+    }
+
+    let mut frontend: Box<dyn Frontend> = if config.terminal {
+        Box::new(frontend::TerminalFrontend::new())
+    } else {
+        Box::new(frontend::MinifbFrontend::new())
+    };
+
     let (_stream, stream_handle) = OutputStream::try_default().unwrap();
     let sink = Sink::try_new(&stream_handle).unwrap();
 
-    let mut display_timer = time::SystemTime::now();
-    while window.is_open() && !window.is_key_down(Key::Escape) {
-        // Get keyboard input and send to chip8
-        for i in 0..INPUT_MAP.len() {
-            chip8.set_key(i, window.is_key_down(INPUT_MAP[i]));
-        }
-
-        // Call next chip8 CPU cycle
-        chip8.update();
+    let state_path = format!("{path}.state");
+    let frame_duration = time::Duration::from_micros(FRAME_MICROS);
 
-        if display_timer.elapsed().unwrap().as_micros() > 16600 {
-            // Print FPS to console
-            //println!("{}", 1.0 / display_timer.elapsed().unwrap().as_secs_f64());
+    // Run on a frame clock rather than sleeping a fixed amount per CPU
+    // cycle: each iteration runs a batch of `instructions_per_frame`
+    // cycles, then sleeps only the remainder of the frame, so wall-clock
+    // drift can't accumulate the way per-cycle sleeps did.
+    while frontend.is_running() {
+        let frame_start = time::Instant::now();
 
-            // Call chip8 draw phase
-            chip8.draw();
+        // Get keyboard input and send to chip8
+        frontend.poll_input(&mut chip8);
+
+        // Run this frame's batch of CPU cycles; timers and the
+        // display-refresh signal are driven off the scheduler inside.
+        let should_redraw = chip8.update(config.instructions_per_frame);
+
+        // Snapshots are only ever taken here, right after `update`
+        // completes and before the next `fetch_instruction`, so a save
+        // always lands on an instruction boundary.
+        if frontend.take_save_request() {
+            match fs::write(&state_path, chip8.save_state()) {
+                Ok(()) => println!("Saved state to {state_path}"),
+                Err(e) => println!("Error: failed to write save state: {e}"),
+            }
+        }
+        if frontend.take_load_request() {
+            match fs::read(&state_path) {
+                Ok(data) => match chip8.load_state(&data) {
+                    Ok(()) => println!("Loaded state from {state_path}"),
+                    Err(e) => println!("{e}"),
+                },
+                Err(e) => println!("Error: failed to read save state: {e}"),
+            }
+        }
 
-            // Update window
-            let buffer = translate_display(chip8.get_display());
-            window
-                .update_with_buffer(&buffer, chip8::DISPLAY_WIDTH, chip8::DISPLAY_HEIGHT)
-                .unwrap();
+        if should_redraw {
+            frontend.draw(&chip8);
 
             // Play audio
             if sink.len() <= 1 { sink.append( SineWave::new(440.0).take_duration(time::Duration::from_secs_f32(0.25)) ) };
             if chip8.get_sound_timer() > 0 { sink.play(); }
             else { sink.pause(); }
-
-            // Reset display timer
-            display_timer = time::SystemTime::now();
         }
 
-        thread::sleep(time::Duration::from_micros(MICROSECONDS_PER_CYCLE as u64));
-    }
-}
-
-fn initialize_window() -> Window {
-    let mut window = Window::new(
-        "chip8-rs",
-        chip8::DISPLAY_WIDTH,
-        chip8::DISPLAY_HEIGHT,
-        WindowOptions {
-            resize: false,
-            scale: minifb::Scale::X8,
-            ..WindowOptions::default()
-        }
-    )
-    .unwrap_or_else(|e| {
-        panic!("{}", e);
-    });
-
-    window.limit_update_rate(None);
-
-    window
-}
-
-// Translates the chip8's monochrome display buffer to a buffer that can be sent to minifb
-fn translate_display(chip8_buffer: &[[bool; chip8::DISPLAY_WIDTH]; chip8::DISPLAY_HEIGHT])
-    -> [u32; chip8::DISPLAY_WIDTH * chip8::DISPLAY_HEIGHT] {
-    let mut window_buffer = [0; chip8::DISPLAY_WIDTH * chip8::DISPLAY_HEIGHT];
-
-    for i in 0..chip8_buffer.len() {
-        for j in 0..chip8_buffer[i].len() {
-            window_buffer[j + (i * chip8::DISPLAY_WIDTH)] = if chip8_buffer[i][j] == true {
-                0xFFFFFFFF
-            } else {
-                0x00000000
-            };
+        let elapsed = frame_start.elapsed();
+        if elapsed < frame_duration {
+            thread::sleep(frame_duration - elapsed);
         }
     }
-
-    window_buffer
 }
 
 fn parse_arguments(args: &[String]) -> Result<Config, &'static str> {
@@ -129,11 +178,56 @@ fn parse_arguments(args: &[String]) -> Result<Config, &'static str> {
     }
 
     let rom_path = args[1].clone();
+    let mut quirks = chip8::Quirks::default();
+    let mut debug_port = None;
+    let mut terminal = false;
+    let mut cpu_hz = DEFAULT_CPU_HZ;
+    let mut ipf = None;
+
+    let mut i = 2;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--quirks" => {
+                let name = args.get(i + 1).ok_or("Error: --quirks requires a value")?;
+                quirks = chip8::Quirks::from_name(name)
+                    .ok_or("Error: unknown --quirks preset (expected cosmac-vip, chip48 or superchip)")?;
+                i += 2;
+            },
+            "--debug" => {
+                let port = args.get(i + 1).ok_or("Error: --debug requires a port")?;
+                debug_port = Some(port.parse::<u16>().map_err(|_| "Error: --debug port must be a number")?);
+                i += 2;
+            },
+            "--terminal" => {
+                terminal = true;
+                i += 1;
+            },
+            "--hz" => {
+                let value = args.get(i + 1).ok_or("Error: --hz requires a value")?;
+                cpu_hz = value.parse().map_err(|_| "Error: --hz must be a number")?;
+                i += 2;
+            },
+            "--ipf" => {
+                let value = args.get(i + 1).ok_or("Error: --ipf requires a value")?;
+                ipf = Some(value.parse::<u32>().map_err(|_| "Error: --ipf must be a number")?.max(1));
+                i += 2;
+            },
+            _ => return Err("Error: unrecognized argument"),
+        }
+    }
+
+    // Default instructions-per-frame to whatever --hz implies for a 60Hz
+    // frame, so --hz alone still behaves like the old fixed clock speed.
+    let instructions_per_frame = ipf.unwrap_or((cpu_hz / 60).max(1));
 
-    Ok(Config { rom_path })
+    Ok(Config { rom_path, quirks, debug_port, terminal, instructions_per_frame })
 }
 
 struct Config {
     rom_path: String,
+    quirks: chip8::Quirks,
+    debug_port: Option<u16>,
+    terminal: bool,
+    instructions_per_frame: u32,
 }
 